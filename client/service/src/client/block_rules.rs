@@ -18,7 +18,11 @@
 
 //! Client fixed chain specification rules
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::RangeInclusive;
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
 
 use sp_runtime::{
 	traits::{Block as BlockT, NumberFor},
@@ -35,7 +39,10 @@ pub enum LookupResult<B: BlockT> {
 	/// The block is known not to be finalized
 	KnownUnfinalized,
 	/// There is a specified canonical block hash for the given height
-	Expected(B::Hash)
+	Expected(B::Hash),
+	/// The block is at the height of a recorded finalized checkpoint but does not
+	/// match the hash recorded there, so it can never become canonical
+	ConflictsWithFinalized(B::Hash),
 }
 
 impl<B: BlockT> LookupResult<B> {
@@ -48,14 +55,118 @@ impl<B: BlockT> LookupResult<B> {
 	}
 }
 
+/// A block identified either by its hash or by its height.
+///
+/// Allows callers that only know one of the two to still query [`BlockRules`].
+/// A hash whose height is known is checked against fork rules and height
+/// ranges; a bare height is checked against the height ranges only.
+pub enum HashOrHeight<B: BlockT> {
+	/// Identify the block by hash.
+	Hash(B::Hash),
+	/// Identify the block by height.
+	Height(NumberFor<B>),
+}
+
+impl<B: BlockT> From<B::Hash> for HashOrHeight<B> {
+	fn from(hash: B::Hash) -> Self {
+		HashOrHeight::Hash(hash)
+	}
+}
+
+impl<B: BlockT> From<NumberFor<B>> for HashOrHeight<B> {
+	fn from(number: NumberFor<B>) -> Self {
+		HashOrHeight::Height(number)
+	}
+}
+
+/// The rules displaced by a call to [`BlockRules::finalize_height`].
+pub struct PrunedRules<B: BlockT> {
+	/// Hashes of the fork, unfinalized and bad rules that were dropped because
+	/// finalization made them irrelevant, so the caller can also discard any
+	/// leaf or header data kept on their behalf.
+	pub displaced: Vec<B::Hash>,
+}
+
+/// Remove every hash from `set` whose height (as looked up in `heights`) is
+/// strictly below `number`, or whose height is exactly `number` but whose hash is
+/// not the one forced canonical there (if any), pushing the removed hashes to
+/// `displaced`.
+///
+/// Hashes with no known height are left untouched: without a height we cannot
+/// tell whether they have been superseded.
+///
+/// `heights` is read-only here on purpose: it is shared by `forks`, `bad` and
+/// `unfinalized`, and the same hash can appear in more than one of those sets at
+/// the same height. Mutating it as a side effect of pruning one set would make a
+/// later call against a different set see a now-missing height and wrongly treat
+/// an otherwise-stale hash as unprunable. Callers are expected to reconcile
+/// `heights` themselves once every set has been pruned against the same snapshot.
+fn stale_in_set<H: std::hash::Hash + Eq + Copy, N: PartialEq + PartialOrd + Copy>(
+	set: &HashSet<H>,
+	heights: &HashMap<H, N>,
+	number: N,
+	canonical_at_number: Option<H>,
+) -> Vec<H> {
+	set.iter()
+		.cloned()
+		.filter(|hash| match heights.get(hash) {
+			Some(height) if *height < number => true,
+			Some(height) if *height == number => {
+				canonical_at_number.map_or(false, |canonical| *hash != canonical)
+			},
+			_ => false,
+		})
+		.collect()
+}
+
+/// Serializable snapshot of the [`BlockRules`] entries that should survive a
+/// restart, suitable for storing as a chain spec extension.
+///
+/// Only `bad`, `forks` and `unfinalized` are persisted: height ranges and
+/// finalization checkpoints are expected to be re-supplied by the chain spec and
+/// the finalized chain itself on every boot.
+#[derive(Serialize, Deserialize)]
+#[serde(bound(serialize = "B::Hash: Serialize, NumberFor<B>: Serialize"))]
+#[serde(bound(deserialize = "B::Hash: Deserialize<'de>, NumberFor<B>: Deserialize<'de>"))]
+pub struct BlockRulesSpec<B: BlockT> {
+	/// Known bad blocks, e.g. via [`BlockRules::mark_bad`], together with their
+	/// height if it was known at the time — carrying the height across a restart
+	/// keeps the entry eligible for [`BlockRules::finalize_height`] pruning.
+	pub bad: HashMap<B::Hash, Option<NumberFor<B>>>,
+	/// Forced canonical blocks, keyed by height.
+	pub forks: HashMap<NumberFor<B>, B::Hash>,
+	/// Blocks marked as not possible to be finalized, e.g. via
+	/// [`BlockRules::mark_unfinalized`], together with their height if it was
+	/// known at the time — carrying the height across a restart keeps the entry
+	/// eligible for [`BlockRules::finalize_height`] pruning.
+	pub unfinalized: HashMap<B::Hash, Option<NumberFor<B>>>,
+}
+
 /// Chain-specific block filtering rules.
 ///
 /// This holds known bad blocks and known good forks, and
 /// is usually part of the chain spec.
 pub struct BlockRules<B: BlockT> {
 	bad: HashSet<B::Hash>,
+	bad_ranges: Vec<RangeInclusive<NumberFor<B>>>,
 	unfinalized: HashSet<B::Hash>,
+	unfinalized_ranges: Vec<RangeInclusive<NumberFor<B>>>,
 	forks: HashMap<NumberFor<B>, B::Hash>,
+	/// Reverse index from hash to height, so that a bare hash can still be checked
+	/// against height-keyed rules such as `forks`, `bad_ranges` and
+	/// `unfinalized_ranges`.
+	heights: HashMap<B::Hash, NumberFor<B>>,
+	/// Height of the highest block recorded via [`Self::record_finalized`], or
+	/// `None` if nothing has been recorded yet.
+	finalized_block_number: Option<NumberFor<B>>,
+	/// Sparse `number -> hash` checkpoints of finalized blocks, used to cheaply
+	/// reject deep reorgs without walking full ancestry. Bounded by
+	/// [`Self::finalize_height`], which drops every checkpoint below the newly
+	/// finalized height except the newest one at or below it.
+	checkpoints: BTreeMap<NumberFor<B>, B::Hash>,
+	/// The checkpoint consulted by the most recent [`Self::lookup`], so that
+	/// repeated queries near the finalized head don't rescan `checkpoints`.
+	checkpoint_cache: Mutex<Option<(NumberFor<B>, B::Hash)>>,
 }
 
 impl<B: BlockT> BlockRules<B> {
@@ -64,31 +175,132 @@ impl<B: BlockT> BlockRules<B> {
 		fork_blocks: ForkBlocks<B>,
 		bad_blocks: BadBlocks<B>,
 	) -> Self {
+		let forks: HashMap<NumberFor<B>, B::Hash> = fork_blocks.unwrap_or(vec![]).into_iter().collect();
+		let heights = forks.iter().map(|(number, hash)| (*hash, *number)).collect();
+
 		Self {
 			bad: bad_blocks.unwrap_or(HashSet::new()),
-			forks: fork_blocks.unwrap_or(vec![]).into_iter().collect(),
+			bad_ranges: Vec::new(),
+			forks,
 			unfinalized: HashSet::new(),
+			unfinalized_ranges: Vec::new(),
+			heights,
+			finalized_block_number: None,
+			checkpoints: BTreeMap::new(),
+			checkpoint_cache: Mutex::new(None),
 		}
 	}
 
+	/// Add height ranges that should be treated as known bad, in addition to the
+	/// single hashes passed to [`Self::new`].
+	///
+	/// Useful for chain specs that need to reject an entire contiguous span of a
+	/// fork without enumerating every hash in it.
+	pub fn with_bad_ranges(mut self, bad_ranges: Vec<RangeInclusive<NumberFor<B>>>) -> Self {
+		self.bad_ranges = bad_ranges;
+		self
+	}
+
+	/// Add height ranges that should be treated as known unfinalized, in addition
+	/// to the hashes added via [`Self::mark_unfinalized`].
+	pub fn with_unfinalized_ranges(
+		mut self,
+		unfinalized_ranges: Vec<RangeInclusive<NumberFor<B>>>,
+	) -> Self {
+		self.unfinalized_ranges = unfinalized_ranges;
+		self
+	}
+
 	/// Mark a block as not possible to be finalized.
-	pub fn mark_unfinalized(&mut self, hash: B::Hash) {
+	///
+	/// If the block's height is known, pass it along so that a later lookup by
+	/// hash alone can still be resolved against height-keyed rules, and so that
+	/// [`Self::finalize_height`] can eventually prune this entry.
+	pub fn mark_unfinalized(&mut self, hash: B::Hash, number: Option<NumberFor<B>>) {
+		if let Some(number) = number {
+			self.heights.insert(hash, number);
+		}
 		self.unfinalized.insert(hash);
 	}
 
+	/// Mark a block as known bad, in addition to the hashes passed to [`Self::new`].
+	///
+	/// If the block's height is known, pass it along so that a later lookup by
+	/// hash alone can still be resolved against height-keyed rules, and so that
+	/// [`Self::finalize_height`] can eventually prune this entry. Bad blocks
+	/// loaded from the chain spec via [`Self::new`] have no known height and are
+	/// therefore never pruned by `finalize_height` — use this method instead of
+	/// relying on the constructor if an entry should be prunable.
+	pub fn mark_bad(&mut self, hash: B::Hash, number: Option<NumberFor<B>>) {
+		if let Some(number) = number {
+			self.heights.insert(hash, number);
+		}
+		self.bad.insert(hash);
+	}
+
+	/// Record a block as finalized, so future lookups can reject anything that
+	/// conflicts with it.
+	pub fn record_finalized(&mut self, number: NumberFor<B>, hash: B::Hash) {
+		self.checkpoints.insert(number, hash);
+		if self.finalized_block_number.map_or(true, |highest| number > highest) {
+			self.finalized_block_number = Some(number);
+		}
+		*self.checkpoint_cache.lock() = Some((number, hash));
+	}
+
+	/// Whether `number` is at or below the highest block passed to
+	/// [`Self::record_finalized`].
+	///
+	/// Returns `false` if [`Self::record_finalized`] has never been called,
+	/// rather than treating an unset finalized height as height zero.
+	pub fn is_below_finalized(&self, number: NumberFor<B>) -> bool {
+		self.finalized_block_number.map_or(false, |highest| number <= highest)
+	}
+
+	/// If a checkpoint is recorded at exactly `number` and `hash` doesn't match
+	/// it, return the checkpoint's hash. Heights without a checkpoint of their
+	/// own never conflict. Caches the checkpoint it consults so that repeated
+	/// lookups near the finalized head don't rescan `checkpoints`.
+	fn conflicts_with_finalized(&self, number: NumberFor<B>, hash: &B::Hash) -> Option<B::Hash> {
+		if !self.is_below_finalized(number) {
+			return None;
+		}
+
+		let cached = *self.checkpoint_cache.lock();
+		if let Some((cached_number, cached_hash)) = cached {
+			if cached_number == number {
+				return if cached_hash != *hash { Some(cached_hash) } else { None };
+			}
+		}
+
+		let (&checkpoint_number, &checkpoint_hash) = self.checkpoints.range(number..).next()?;
+		if checkpoint_number != number {
+			return None;
+		}
+
+		*self.checkpoint_cache.lock() = Some((checkpoint_number, checkpoint_hash));
+		if checkpoint_hash != *hash { Some(checkpoint_hash) } else { None }
+	}
+
 	/// Check if there's any rule affecting the given block.
 	pub fn lookup(&self, number: NumberFor<B>, hash: &B::Hash) -> LookupResult<B> {
+		if let Some(expected) = self.conflicts_with_finalized(number, hash) {
+			return LookupResult::ConflictsWithFinalized(expected);
+		}
+
 		if let Some(hash_for_height) = self.forks.get(&number) {
 			if hash_for_height != hash {
 				return LookupResult::Expected(hash_for_height.clone());
 			}
 		}
 
-		if self.bad.contains(hash) {
+		if self.bad.contains(hash) || self.bad_ranges.iter().any(|range| range.contains(&number)) {
 			return LookupResult::KnownBad
 		}
 
-		if self.unfinalized.contains(hash) {
+		if self.unfinalized.contains(hash)
+			|| self.unfinalized_ranges.iter().any(|range| range.contains(&number))
+		{
 			return LookupResult::KnownUnfinalized
 		}
 
@@ -107,4 +319,460 @@ impl<B: BlockT> BlockRules<B> {
 
 		LookupResult::NotSpecial
 	}
+
+	/// Check if there's any rule affecting the given block, identified either by
+	/// hash or by height.
+	///
+	/// When only a hash is given, its height is recovered from the internal
+	/// hash-to-height index (populated from the fork list and from heights passed
+	/// to [`Self::mark_unfinalized`] and [`Self::mark_bad`]) so that height-keyed rules can still be
+	/// evaluated; if the height is unknown, this falls back to [`Self::lookup_hash`].
+	pub fn lookup_by(&self, id: HashOrHeight<B>) -> LookupResult<B> {
+		match id {
+			HashOrHeight::Hash(hash) => match self.heights.get(&hash) {
+				Some(number) => self.lookup(*number, &hash),
+				None => self.lookup_hash(&hash),
+			},
+			HashOrHeight::Height(number) => {
+				if self.bad_ranges.iter().any(|range| range.contains(&number)) {
+					return LookupResult::KnownBad
+				}
+
+				if self.unfinalized_ranges.iter().any(|range| range.contains(&number)) {
+					return LookupResult::KnownUnfinalized
+				}
+
+				LookupResult::NotSpecial
+			}
+		}
+	}
+
+	/// Return the forced canonical hash for the given height, if any.
+	pub fn canonical_at(&self, number: NumberFor<B>) -> Option<B::Hash> {
+		self.forks.get(&number).cloned()
+	}
+
+	/// Prune every rule made irrelevant by finalizing up to `number`.
+	///
+	/// This discards fork, unfinalized and bad entries strictly below `number`
+	/// (their height has been superseded and will never be revisited), along with
+	/// any entry still sitting at exactly `number` whose hash is not the one just
+	/// forced canonical there. Only entries whose height is known — via the fork
+	/// list, or a height passed to [`Self::mark_unfinalized`]/[`Self::mark_bad`] —
+	/// can be pruned this way; bad blocks loaded from the chain spec via
+	/// [`Self::new`] carry no height and are left untouched.
+	///
+	/// The key invariant: after `finalize_height(n)` returns, no fork rule and no
+	/// bad or unfinalized hash with a known height below `n` remains, so
+	/// downstream header pruning never needs to consult `BlockRules` for ancestry
+	/// below the finalized height for any entry it knows the height of. Height
+	/// ranges added via [`Self::with_bad_ranges`] and
+	/// [`Self::with_unfinalized_ranges`] are not pruned. This also bounds
+	/// `checkpoints`, which would otherwise grow by one entry per
+	/// [`Self::record_finalized`] call.
+	pub fn finalize_height(&mut self, number: NumberFor<B>) -> PrunedRules<B> {
+		let mut displaced = Vec::new();
+		let canonical_at_number = self.forks.get(&number).cloned();
+		// Evaluate staleness for `bad` and `unfinalized` against one snapshot of
+		// `heights`, taken before either set is touched. The same hash can be
+		// tracked in both sets at the same height; pruning `heights` as a side
+		// effect of the first set would make the second set's check see a missing
+		// height and wrongly treat the hash as unprunable.
+		let heights_snapshot = self.heights.clone();
+
+		let stale_forks: Vec<NumberFor<B>> = self.forks.keys()
+			.cloned()
+			.filter(|height| *height < number)
+			.collect();
+		for height in stale_forks {
+			if let Some(hash) = self.forks.remove(&height) {
+				displaced.push(hash);
+			}
+		}
+		// The rule at exactly `number` has served its purpose now that the block
+		// has been finalized; its hash is canonical and must not be reported as
+		// displaced.
+		self.forks.remove(&number);
+
+		let stale_bad = stale_in_set(&self.bad, &heights_snapshot, number, canonical_at_number);
+		for hash in &stale_bad {
+			self.bad.remove(hash);
+		}
+		displaced.extend(stale_bad);
+
+		let stale_unfinalized =
+			stale_in_set(&self.unfinalized, &heights_snapshot, number, canonical_at_number);
+		for hash in &stale_unfinalized {
+			self.unfinalized.remove(hash);
+		}
+		displaced.extend(stale_unfinalized);
+
+		// Now that every set has been pruned against the same snapshot, drop the
+		// `heights` entry for any hash no longer referenced by any of them.
+		let still_referenced: HashSet<B::Hash> = self.forks.values().cloned()
+			.chain(self.bad.iter().cloned())
+			.chain(self.unfinalized.iter().cloned())
+			.collect();
+		self.heights.retain(|hash, _| still_referenced.contains(hash));
+
+		// `checkpoints` is documented as sparse and bounded; without this it would
+		// grow by one entry for every call to `record_finalized`. Keep only the
+		// newest entry at or below `number` as an anchor, and drop the other
+		// entries below `number`: lookups at their heights no longer find a
+		// checkpoint in this map.
+		let anchor = self.checkpoints.range(..=number).next_back().map(|(&h, &hash)| (h, hash));
+		self.checkpoints.retain(|height, _| *height >= number);
+		if let Some((height, hash)) = anchor {
+			self.checkpoints.entry(height).or_insert(hash);
+		}
+
+		PrunedRules { displaced }
+	}
+
+	/// Snapshot the persistable parts of these rules for storing in a chain spec
+	/// extension.
+	pub fn to_spec_extension(&self) -> BlockRulesSpec<B> {
+		BlockRulesSpec {
+			bad: self.bad.iter()
+				.map(|hash| (*hash, self.heights.get(hash).cloned()))
+				.collect(),
+			forks: self.forks.clone(),
+			unfinalized: self.unfinalized.iter()
+				.map(|hash| (*hash, self.heights.get(hash).cloned()))
+				.collect(),
+		}
+	}
+
+	/// Build block rules directly from a persisted [`BlockRulesSpec`], with no
+	/// height ranges or finalization checkpoints.
+	pub fn from_spec_extension(spec: BlockRulesSpec<B>) -> Self {
+		let mut rules = Self::new(Some(spec.forks.into_iter().collect()), None);
+		for (hash, number) in spec.bad {
+			rules.mark_bad(hash, number);
+		}
+		for (hash, number) in spec.unfinalized {
+			rules.mark_unfinalized(hash, number);
+		}
+		rules
+	}
+
+	/// Fold a persisted [`BlockRulesSpec`] into these rules, e.g. to merge
+	/// runtime-applied rules saved on a previous run into the ones just loaded
+	/// from the chain spec at startup.
+	pub fn merge(&mut self, persisted: BlockRulesSpec<B>) {
+		for (hash, number) in persisted.bad {
+			self.mark_bad(hash, number);
+		}
+		for (number, hash) in persisted.forks {
+			self.forks.insert(number, hash);
+			self.heights.insert(hash, number);
+		}
+		for (hash, number) in persisted.unfinalized {
+			self.mark_unfinalized(hash, number);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use substrate_test_runtime_client::runtime::{Block, Hash};
+
+	fn rules() -> BlockRules<Block> {
+		BlockRules::new(None, None)
+	}
+
+	fn hash(seed: u8) -> Hash {
+		Hash::repeat_byte(seed)
+	}
+
+	#[test]
+	fn bad_range_matches_any_hash_in_range() {
+		let rules = rules().with_bad_ranges(vec![10..=20]);
+
+		assert!(matches!(rules.lookup(15, &hash(1)), LookupResult::KnownBad));
+		assert!(matches!(rules.lookup(10, &hash(2)), LookupResult::KnownBad));
+		assert!(matches!(rules.lookup(20, &hash(3)), LookupResult::KnownBad));
+		assert!(matches!(rules.lookup(21, &hash(4)), LookupResult::NotSpecial));
+		assert!(matches!(rules.lookup(9, &hash(5)), LookupResult::NotSpecial));
+	}
+
+	#[test]
+	fn unfinalized_range_matches_any_hash_in_range() {
+		let rules = rules().with_unfinalized_ranges(vec![100..=200]);
+
+		assert!(matches!(rules.lookup(150, &hash(1)), LookupResult::KnownUnfinalized));
+		assert!(matches!(rules.lookup(99, &hash(1)), LookupResult::NotSpecial));
+		assert!(matches!(rules.lookup(201, &hash(1)), LookupResult::NotSpecial));
+	}
+
+	#[test]
+	fn lookup_hash_is_unaffected_by_ranges() {
+		// `lookup_hash` only has a hash to go on, so a range rule (keyed by
+		// height) must never apply to it.
+		let rules = rules().with_bad_ranges(vec![0..=1_000]);
+
+		assert!(matches!(rules.lookup_hash(&hash(1)), LookupResult::NotSpecial));
+	}
+
+	#[test]
+	fn lookup_by_hash_resolves_height_via_reverse_index() {
+		let mut rules = rules();
+		// Only known through `mark_unfinalized`'s optional height, not a fork entry.
+		rules.mark_unfinalized(hash(1), Some(42));
+
+		assert!(matches!(
+			rules.lookup_by(HashOrHeight::Hash(hash(1))),
+			LookupResult::KnownUnfinalized,
+		));
+	}
+
+	#[test]
+	fn lookup_by_hash_without_known_height_falls_back_to_lookup_hash() {
+		let mut rules = rules();
+		rules.mark_unfinalized(hash(1), None);
+
+		assert!(matches!(
+			rules.lookup_by(HashOrHeight::Hash(hash(1))),
+			LookupResult::KnownUnfinalized,
+		));
+		// A hash never marked at all is simply not special.
+		assert!(matches!(
+			rules.lookup_by(HashOrHeight::Hash(hash(2))),
+			LookupResult::NotSpecial,
+		));
+	}
+
+	#[test]
+	fn lookup_by_height_checks_ranges() {
+		let rules = rules().with_bad_ranges(vec![5..=5]);
+
+		assert!(matches!(rules.lookup_by(HashOrHeight::Height(5)), LookupResult::KnownBad));
+		assert!(matches!(rules.lookup_by(HashOrHeight::Height(6)), LookupResult::NotSpecial));
+	}
+
+	#[test]
+	fn canonical_at_returns_forced_fork_hash() {
+		let rules = BlockRules::<Block>::new(Some(vec![(7, hash(1))]), None);
+
+		assert_eq!(rules.canonical_at(7), Some(hash(1)));
+		assert_eq!(rules.canonical_at(8), None);
+	}
+
+	#[test]
+	fn finalize_height_prunes_entries_strictly_below_and_reports_them_displaced() {
+		let mut rules = BlockRules::<Block>::new(Some(vec![(5, hash(1))]), None);
+		rules.mark_unfinalized(hash(2), Some(5));
+		rules.mark_bad(hash(3), Some(3));
+
+		let pruned = rules.finalize_height(10);
+
+		assert!(pruned.displaced.contains(&hash(2)));
+		assert!(pruned.displaced.contains(&hash(3)));
+		// The invariant `finalize_height` documents: nothing keyed below `n` remains.
+		assert!(matches!(rules.lookup_by(HashOrHeight::Hash(hash(2))), LookupResult::NotSpecial));
+		assert!(matches!(rules.lookup_by(HashOrHeight::Hash(hash(3))), LookupResult::NotSpecial));
+		assert_eq!(rules.canonical_at(5), None);
+	}
+
+	#[test]
+	fn finalize_height_drops_same_height_entries_that_lost_to_the_canonical_fork() {
+		let mut rules = BlockRules::<Block>::new(Some(vec![(5, hash(1))]), None);
+		// A competing hash at the same height as the forced fork rule.
+		rules.mark_unfinalized(hash(2), Some(5));
+
+		let pruned = rules.finalize_height(5);
+
+		assert!(pruned.displaced.contains(&hash(2)));
+		// The canonical hash itself must never be reported as displaced.
+		assert!(!pruned.displaced.contains(&hash(1)));
+	}
+
+	#[test]
+	fn finalize_height_leaves_unknown_height_entries_untouched() {
+		// `bad` entries loaded via `new` (the chain-spec path) carry no height and
+		// so cannot be pruned; this documents that limitation rather than hiding it.
+		let mut rules = BlockRules::<Block>::new(None, Some(vec![hash(1)].into_iter().collect()));
+
+		let pruned = rules.finalize_height(1_000);
+
+		assert!(pruned.displaced.is_empty());
+		assert!(matches!(rules.lookup_hash(&hash(1)), LookupResult::KnownBad));
+	}
+
+	#[test]
+	fn finalize_height_prunes_a_hash_tracked_as_both_bad_and_unfinalized_at_the_same_height() {
+		// The same hash can end up in both `bad` and `unfinalized` (e.g. marked bad
+		// after already being marked unfinalized). Both sets share the `heights`
+		// index, so pruning `bad` first must not blind the `unfinalized` check to
+		// this hash's height.
+		let mut rules = rules();
+		rules.mark_unfinalized(hash(7), Some(1));
+		rules.mark_bad(hash(7), Some(1));
+
+		let pruned = rules.finalize_height(10);
+
+		assert!(matches!(rules.lookup_by(HashOrHeight::Hash(hash(7))), LookupResult::NotSpecial));
+		assert!(pruned.displaced.contains(&hash(7)));
+	}
+
+	#[test]
+	fn mark_bad_with_height_is_prunable() {
+		let mut rules = rules();
+		rules.mark_bad(hash(1), Some(1));
+
+		assert!(matches!(rules.lookup_hash(&hash(1)), LookupResult::KnownBad));
+
+		let pruned = rules.finalize_height(2);
+
+		assert!(pruned.displaced.contains(&hash(1)));
+		assert!(matches!(rules.lookup_hash(&hash(1)), LookupResult::NotSpecial));
+	}
+
+	#[test]
+	fn is_below_finalized_is_false_until_something_is_recorded() {
+		let rules = rules();
+
+		// Must not be conflated with a genuine "finalized at height zero".
+		assert!(!rules.is_below_finalized(0));
+	}
+
+	#[test]
+	fn is_below_finalized_tracks_the_highest_recorded_height() {
+		let mut rules = rules();
+		rules.record_finalized(10, hash(1));
+
+		assert!(rules.is_below_finalized(0));
+		assert!(rules.is_below_finalized(10));
+		assert!(!rules.is_below_finalized(11));
+	}
+
+	#[test]
+	fn lookup_conflicts_with_a_mismatched_checkpoint() {
+		let mut rules = rules();
+		rules.record_finalized(10, hash(1));
+
+		assert!(matches!(
+			rules.lookup(10, &hash(2)),
+			LookupResult::ConflictsWithFinalized(expected) if expected == hash(1),
+		));
+	}
+
+	#[test]
+	fn lookup_does_not_conflict_with_a_matching_checkpoint() {
+		let mut rules = rules();
+		rules.record_finalized(10, hash(1));
+
+		assert!(matches!(rules.lookup(10, &hash(1)), LookupResult::NotSpecial));
+	}
+
+	#[test]
+	fn repeated_lookup_reuses_the_cached_checkpoint() {
+		let mut rules = rules();
+		rules.record_finalized(10, hash(1));
+		// `record_finalized` has already filled the cache; with the map emptied,
+		// only the cached checkpoint can answer the repeated lookups below.
+		rules.checkpoints.clear();
+
+		for _ in 0..3 {
+			assert!(matches!(
+				rules.lookup(10, &hash(2)),
+				LookupResult::ConflictsWithFinalized(expected) if expected == hash(1),
+			));
+		}
+	}
+
+	#[test]
+	fn finalize_height_bounds_the_checkpoints_map() {
+		// Without pruning, `checkpoints` would grow by one entry per
+		// `record_finalized` call, contradicting its doc as a bounded, sparse map.
+		let mut rules = rules();
+		rules.record_finalized(1, hash(1));
+		rules.record_finalized(2, hash(2));
+		rules.record_finalized(3, hash(3));
+
+		rules.finalize_height(3);
+
+		assert_eq!(rules.checkpoints.len(), 1);
+		assert!(rules.checkpoints.contains_key(&3));
+	}
+
+	#[test]
+	fn finalize_height_keeps_conflicts_with_finalized_working_after_pruning() {
+		let mut rules = rules();
+		rules.record_finalized(5, hash(1));
+		rules.record_finalized(10, hash(2));
+
+		rules.finalize_height(10);
+
+		assert!(matches!(
+			rules.lookup(10, &hash(9)),
+			LookupResult::ConflictsWithFinalized(expected) if expected == hash(2),
+		));
+	}
+
+	#[test]
+	fn spec_extension_round_trips_through_serde() {
+		let mut rules = BlockRules::<Block>::new(
+			Some(vec![(7, hash(1))]),
+			Some(vec![hash(2)].into_iter().collect()),
+		);
+		rules.mark_unfinalized(hash(3), Some(9));
+
+		let spec = rules.to_spec_extension();
+		let encoded = serde_json::to_vec(&spec).expect("BlockRulesSpec is serializable");
+		let decoded: BlockRulesSpec<Block> =
+			serde_json::from_slice(&encoded).expect("BlockRulesSpec is deserializable");
+
+		let restored = BlockRules::<Block>::from_spec_extension(decoded);
+		assert!(matches!(restored.lookup_hash(&hash(2)), LookupResult::KnownBad));
+		assert_eq!(restored.canonical_at(7), Some(hash(1)));
+		assert!(matches!(restored.lookup_hash(&hash(3)), LookupResult::KnownUnfinalized));
+	}
+
+	#[test]
+	fn spec_extension_preserves_unfinalized_height_so_it_stays_prunable() {
+		let mut rules = rules();
+		rules.mark_unfinalized(hash(1), Some(1));
+
+		// Round-trip through the persisted representation, as would happen across
+		// a restart.
+		let spec = rules.to_spec_extension();
+		let mut restored = BlockRules::<Block>::from_spec_extension(spec);
+
+		let pruned = restored.finalize_height(2);
+		assert!(pruned.displaced.contains(&hash(1)));
+	}
+
+	#[test]
+	fn spec_extension_preserves_bad_height_so_it_stays_prunable() {
+		// Symmetric with `spec_extension_preserves_unfinalized_height_so_it_stays_prunable`:
+		// a runtime-`mark_bad`'d hash must not become permanently un-prunable after
+		// a restart just because it was persisted.
+		let mut rules = rules();
+		rules.mark_bad(hash(1), Some(1));
+
+		let spec = rules.to_spec_extension();
+		let mut restored = BlockRules::<Block>::from_spec_extension(spec);
+
+		let pruned = restored.finalize_height(2);
+		assert!(pruned.displaced.contains(&hash(1)));
+	}
+
+	#[test]
+	fn merge_folds_persisted_rules_into_existing_ones() {
+		let mut rules = BlockRules::<Block>::new(None, None);
+		rules.mark_unfinalized(hash(1), None);
+
+		let mut persisted_source = BlockRules::<Block>::new(None, None);
+		persisted_source.mark_unfinalized(hash(2), Some(4));
+		let persisted = persisted_source.to_spec_extension();
+
+		rules.merge(persisted);
+
+		assert!(matches!(rules.lookup_hash(&hash(1)), LookupResult::KnownUnfinalized));
+		assert!(matches!(rules.lookup_hash(&hash(2)), LookupResult::KnownUnfinalized));
+		let pruned = rules.finalize_height(5);
+		assert!(pruned.displaced.contains(&hash(2)));
+	}
 }
@@ -0,0 +1,255 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2020 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! A `SelectChain` that honors `BlockRules` when choosing finalization and
+//! authoring targets.
+
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use sp_consensus::{Error as ConsensusError, SelectChain};
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT, NumberFor};
+
+use super::block_rules::{BlockRules, LookupResult};
+
+/// A `SelectChain` wrapper that consults a shared [`BlockRules`] before
+/// delegating to an inner `SelectChain`.
+///
+/// Any candidate `lookup_hash` reports as `KnownUnfinalized` is excluded from
+/// finalization targets, and any `KnownBad` candidate is excluded from both
+/// authoring (`best_chain` errors rather than authoring on top of it, and
+/// `leaves` omits it) and finalization. The same rule set mutated via
+/// `BlockRules::mark_unfinalized` at import time therefore immediately affects
+/// chain selection.
+#[derive(Clone)]
+pub struct RuleAwareSelectChain<B: BlockT, S> {
+	inner: S,
+	rules: Arc<RwLock<BlockRules<B>>>,
+}
+
+impl<B: BlockT, S> RuleAwareSelectChain<B, S> {
+	/// Wrap `inner`, filtering its choices through `rules`.
+	pub fn new(inner: S, rules: Arc<RwLock<BlockRules<B>>>) -> Self {
+		Self { inner, rules }
+	}
+
+	fn is_known_bad(&self, hash: &B::Hash) -> bool {
+		matches!(self.rules.read().lookup_hash(hash), LookupResult::KnownBad)
+	}
+
+	/// Like [`Self::is_known_bad`], but also consults height-keyed rules (bad
+	/// height ranges and finalization checkpoints) via the full `lookup`, which
+	/// `lookup_hash` deliberately cannot evaluate on its own.
+	fn is_excluded_by_height(&self, number: NumberFor<B>, hash: &B::Hash) -> bool {
+		let rules = self.rules.read();
+		match rules.lookup(number, hash) {
+			LookupResult::KnownBad | LookupResult::ConflictsWithFinalized(_) => true,
+			// `lookup` reports a fork-rule mismatch before it checks `bad`, so a
+			// known bad hash at a fork height needs the hash-only check as well.
+			_ => matches!(rules.lookup_hash(hash), LookupResult::KnownBad),
+		}
+	}
+
+	fn is_excluded_from_finalization(&self, hash: &B::Hash) -> bool {
+		match self.rules.read().lookup_hash(hash) {
+			LookupResult::KnownBad | LookupResult::KnownUnfinalized => true,
+			_ => false,
+		}
+	}
+}
+
+impl<B, S> SelectChain<B> for RuleAwareSelectChain<B, S>
+where
+	B: BlockT,
+	S: SelectChain<B>,
+{
+	fn leaves(&self) -> Result<Vec<B::Hash>, ConsensusError> {
+		let leaves = self.inner.leaves()?;
+		Ok(leaves.into_iter().filter(|hash| !self.is_known_bad(hash)).collect())
+	}
+
+	fn best_chain(&self) -> Result<B::Header, ConsensusError> {
+		let best = self.inner.best_chain()?;
+		// `best` carries both hash and number, so use the full `lookup` here
+		// rather than `lookup_hash`: only `lookup` can catch a candidate that
+		// falls inside a declared bad height range or conflicts with a recorded
+		// finalization checkpoint.
+		if self.is_excluded_by_height(*best.number(), &best.hash()) {
+			// We have no way to rank the remaining, non-bad leaves ourselves (that
+			// requires backend access `SelectChain` doesn't expose), so refuse to
+			// author on top of an excluded block rather than silently returning it.
+			return Err(ConsensusError::ClientImport(format!(
+				"best chain candidate {:?} is excluded by BlockRules",
+				best.hash(),
+			)));
+		}
+		Ok(best)
+	}
+
+	fn finality_target(
+		&self,
+		target_hash: B::Hash,
+		maybe_max_number: Option<NumberFor<B>>,
+	) -> Result<Option<B::Hash>, ConsensusError> {
+		match self.inner.finality_target(target_hash, maybe_max_number)? {
+			Some(hash) if self.is_excluded_from_finalization(&hash) => Ok(None),
+			other => Ok(other),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_runtime::generic::Digest;
+	use substrate_test_runtime_client::runtime::{Block, Hash};
+
+	#[derive(Clone)]
+	struct MockSelectChain {
+		leaves: Vec<Hash>,
+		best: <Block as BlockT>::Header,
+		finality_target: Option<Hash>,
+	}
+
+	impl SelectChain<Block> for MockSelectChain {
+		fn leaves(&self) -> Result<Vec<Hash>, ConsensusError> {
+			Ok(self.leaves.clone())
+		}
+
+		fn best_chain(&self) -> Result<<Block as BlockT>::Header, ConsensusError> {
+			Ok(self.best.clone())
+		}
+
+		fn finality_target(
+			&self,
+			_target_hash: Hash,
+			_maybe_max_number: Option<NumberFor<Block>>,
+		) -> Result<Option<Hash>, ConsensusError> {
+			Ok(self.finality_target)
+		}
+	}
+
+	fn hash(seed: u8) -> Hash {
+		Hash::repeat_byte(seed)
+	}
+
+	fn header_with_hash(number: u64, parent: Hash) -> <Block as BlockT>::Header {
+		HeaderT::new(number, Default::default(), Default::default(), parent, Digest::default())
+	}
+
+	fn wrap(
+		best: <Block as BlockT>::Header,
+		leaves: Vec<Hash>,
+		finality_target: Option<Hash>,
+		rules: BlockRules<Block>,
+	) -> RuleAwareSelectChain<Block, MockSelectChain> {
+		RuleAwareSelectChain::new(
+			MockSelectChain { leaves, best, finality_target },
+			Arc::new(RwLock::new(rules)),
+		)
+	}
+
+	#[test]
+	fn leaves_excludes_known_bad() {
+		let mut rules = BlockRules::<Block>::new(None, None);
+		rules.mark_bad(hash(2), None);
+
+		let select_chain = wrap(
+			header_with_hash(1, hash(0)),
+			vec![hash(1), hash(2)],
+			None,
+			rules,
+		);
+
+		assert_eq!(select_chain.leaves().unwrap(), vec![hash(1)]);
+	}
+
+	#[test]
+	fn best_chain_errors_when_candidate_is_known_bad() {
+		let mut rules = BlockRules::<Block>::new(None, None);
+		let best = header_with_hash(1, hash(0));
+		rules.mark_bad(best.hash(), None);
+
+		let select_chain = wrap(best, vec![], None, rules);
+
+		assert!(select_chain.best_chain().is_err());
+	}
+
+	#[test]
+	fn best_chain_passes_through_when_not_excluded() {
+		let rules = BlockRules::<Block>::new(None, None);
+		let best = header_with_hash(1, hash(0));
+
+		let select_chain = wrap(best.clone(), vec![], None, rules);
+
+		assert_eq!(select_chain.best_chain().unwrap(), best);
+	}
+
+	#[test]
+	fn best_chain_errors_when_candidate_is_in_a_bad_height_range() {
+		// `lookup_hash` alone can never see this: only the height-aware `lookup`
+		// knows about `bad_ranges`.
+		let rules = BlockRules::<Block>::new(None, None).with_bad_ranges(vec![1..=1]);
+		let best = header_with_hash(1, hash(0));
+
+		let select_chain = wrap(best, vec![], None, rules);
+
+		assert!(select_chain.best_chain().is_err());
+	}
+
+	#[test]
+	fn best_chain_errors_when_candidate_conflicts_with_a_finalized_checkpoint() {
+		let mut rules = BlockRules::<Block>::new(None, None);
+		let best = header_with_hash(1, hash(9));
+		rules.record_finalized(1, hash(42));
+
+		let select_chain = wrap(best, vec![], None, rules);
+
+		assert!(select_chain.best_chain().is_err());
+	}
+
+	#[test]
+	fn finality_target_excludes_known_unfinalized() {
+		let mut rules = BlockRules::<Block>::new(None, None);
+		rules.mark_unfinalized(hash(1), None);
+
+		let select_chain = wrap(header_with_hash(1, hash(0)), vec![], Some(hash(1)), rules);
+
+		assert_eq!(select_chain.finality_target(hash(1), None).unwrap(), None);
+	}
+
+	#[test]
+	fn finality_target_excludes_known_bad() {
+		let mut rules = BlockRules::<Block>::new(None, None);
+		rules.mark_bad(hash(1), None);
+
+		let select_chain = wrap(header_with_hash(1, hash(0)), vec![], Some(hash(1)), rules);
+
+		assert_eq!(select_chain.finality_target(hash(1), None).unwrap(), None);
+	}
+
+	#[test]
+	fn finality_target_passes_through_when_not_excluded() {
+		let rules = BlockRules::<Block>::new(None, None);
+
+		let select_chain = wrap(header_with_hash(1, hash(0)), vec![], Some(hash(1)), rules);
+
+		assert_eq!(select_chain.finality_target(hash(1), None).unwrap(), Some(hash(1)));
+	}
+}